@@ -1,5 +1,6 @@
 use chrono::{self, Datelike, NaiveDate};
 use csv::Reader;
+use plotters::prelude::*;
 use serde;
 use std::collections::HashMap;
 
@@ -43,6 +44,241 @@ struct PPDSRecord {
     price_paid: i32,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+enum Cell {
+    Date(NaiveDate),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Empty,
+}
+
+impl Cell {
+    fn as_date(&self) -> Option<NaiveDate> {
+        match self {
+            Cell::Date(d) => Some(*d),
+            _ => None,
+        }
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            Cell::Int(i) => Some(*i as f64),
+            Cell::Float(f) => Some(*f),
+            _ => None,
+        }
+    }
+
+    fn to_display_string(&self) -> String {
+        match self {
+            Cell::Date(d) => d.format("%Y-%m-%d").to_string(),
+            Cell::Int(i) => format!("{i}"),
+            Cell::Float(f) => format!("{f}"),
+            Cell::Str(s) => s.clone(),
+            Cell::Empty => String::new(),
+        }
+    }
+}
+
+/// A small column-oriented dataset used by `write_all_sale_map` and, for a
+/// single region-average lookup, by `filter_and_write`. It isn't a wholesale
+/// replacement for `create_ppd_mapping`/`create_reference_mapping` — those
+/// still build their own `HashMap`s, which the rest of `filter_and_write`
+/// indexes directly — but `join` gives those two call sites full-outer-join
+/// semantics declaratively instead of a bespoke loop.
+#[derive(Debug, Clone)]
+struct DataFrame {
+    columns: Vec<String>,
+    rows: Vec<Vec<Cell>>,
+}
+
+impl DataFrame {
+    fn new(columns: Vec<&str>) -> DataFrame {
+        DataFrame {
+            columns: columns.into_iter().map(String::from).collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    fn push_row(&mut self, row: Vec<Cell>) {
+        debug_assert_eq!(row.len(), self.columns.len());
+        self.rows.push(row);
+    }
+
+    fn column_index(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|c| c == name)
+    }
+
+    fn rename(&mut self, old: &str, new: &str) {
+        if let Some(idx) = self.column_index(old) {
+            self.columns[idx] = new.to_string();
+        }
+    }
+
+    fn select(&self, columns: &[&str]) -> DataFrame {
+        let idxs: Vec<usize> = columns
+            .iter()
+            .map(|c| self.column_index(c).expect("unknown column"))
+            .collect();
+
+        let mut out = DataFrame::new(columns.to_vec());
+        for row in &self.rows {
+            out.rows.push(idxs.iter().map(|i| row[*i].clone()).collect());
+        }
+
+        out
+    }
+
+    /// Returns a copy of this `DataFrame` with every date in `date_column`
+    /// floored to the first of its month.
+    fn floor_to_month(&self, date_column: &str) -> DataFrame {
+        let idx = self.column_index(date_column).expect("unknown column");
+
+        let mut out = DataFrame {
+            columns: self.columns.clone(),
+            rows: Vec::new(),
+        };
+
+        for row in &self.rows {
+            let mut new_row = row.clone();
+            if let Some(d) = row[idx].as_date() {
+                new_row[idx] = Cell::Date(d.with_day(1).unwrap_or(d));
+            }
+            out.rows.push(new_row);
+        }
+
+        out
+    }
+
+    /// Full outer join of `self` to `other` on matching cells in `self_key`/
+    /// `other_key`. Rows with no match on either side are kept, with the
+    /// columns that came from the other side left `Cell::Empty` — this is
+    /// what replaces the manual `ref_map[&(month, year)]` lookups that used
+    /// to assume a matching key always existed.
+    fn join(&self, other: &DataFrame, self_key: &str, other_key: &str) -> DataFrame {
+        let self_idx = self.column_index(self_key).expect("unknown join column");
+        let other_idx = other.column_index(other_key).expect("unknown join column");
+
+        let other_value_idxs: Vec<usize> =
+            (0..other.columns.len()).filter(|i| *i != other_idx).collect();
+
+        let mut columns = self.columns.clone();
+        for i in &other_value_idxs {
+            columns.push(other.columns[*i].clone());
+        }
+
+        let mut out = DataFrame {
+            columns,
+            rows: Vec::new(),
+        };
+
+        let mut other_matched = vec![false; other.rows.len()];
+
+        for row in &self.rows {
+            let mut matched = false;
+            for (j, other_row) in other.rows.iter().enumerate() {
+                if row[self_idx] == other_row[other_idx] {
+                    matched = true;
+                    other_matched[j] = true;
+
+                    let mut new_row = row.clone();
+                    for i in &other_value_idxs {
+                        new_row.push(other_row[*i].clone());
+                    }
+                    out.rows.push(new_row);
+                }
+            }
+
+            if !matched {
+                let mut new_row = row.clone();
+                new_row.extend(other_value_idxs.iter().map(|_| Cell::Empty));
+                out.rows.push(new_row);
+            }
+        }
+
+        for (j, other_row) in other.rows.iter().enumerate() {
+            if !other_matched[j] {
+                let mut new_row = vec![Cell::Empty; self.columns.len()];
+                new_row[self_idx] = other_row[other_idx].clone();
+                for i in &other_value_idxs {
+                    new_row.push(other_row[*i].clone());
+                }
+                out.rows.push(new_row);
+            }
+        }
+
+        out
+    }
+
+    /// Converts to the wide string-row `OutputCSV` shape the rest of the
+    /// crate writes and charts, sorted by `date_column`.
+    fn to_output_csv(&self, date_column: &str) -> OutputCSV {
+        let date_idx = self.column_index(date_column).expect("unknown date column");
+        let value_idxs: Vec<usize> = (0..self.columns.len()).filter(|i| *i != date_idx).collect();
+
+        let mut output = OutputCSV::new();
+        output.set_labels(
+            std::iter::once("date".to_string())
+                .chain(value_idxs.iter().map(|i| self.columns[*i].clone()))
+                .collect(),
+        );
+
+        let mut rows: Vec<&Vec<Cell>> = self.rows.iter().collect();
+        rows.sort_by_key(|r| r[date_idx].as_date());
+
+        for row in rows {
+            let mut new_row = vec![row[date_idx]
+                .as_date()
+                .map(|d| d.format("%Y-%m-%d").to_string())
+                .unwrap_or_default()];
+            new_row.extend(value_idxs.iter().map(|i| row[*i].to_display_string()));
+            output.add_row(new_row);
+        }
+
+        output
+    }
+}
+
+fn create_ppd_dataframe(filename: &str) -> DataFrame {
+    let mut df = DataFrame::new(vec!["date", "flat", "building", "estate", "price_paid"]);
+    let mut reader = Reader::from_path(filename).unwrap();
+
+    for result in reader.deserialize::<PPDSRecord>() {
+        match result {
+            Ok(n) => df.push_row(vec![
+                Cell::Date(n.date),
+                Cell::Str(n.flat_name),
+                Cell::Str(n.building),
+                Cell::Str(n.estate),
+                Cell::Int(n.price_paid as i64),
+            ]),
+            Err(e) => println!("{e:?}"),
+        }
+    }
+
+    df
+}
+
+/// Converts an already-built reference `HashMap` (as returned by
+/// `create_reference_mapping`) into a `DataFrame`, so callers that already
+/// hold one don't have to re-read the CSV.
+fn reference_map_to_dataframe(map: &HashMap<(i32, i32), UKHPIRecord>) -> DataFrame {
+    let mut df = DataFrame::new(vec!["date", "region", "average_price_flats"]);
+
+    let mut records: Vec<&UKHPIRecord> = map.values().collect();
+    records.sort_by_key(|r| r.time);
+
+    for r in records {
+        df.push_row(vec![
+            Cell::Date(r.time),
+            Cell::Str(r.region.clone()),
+            Cell::Int(r.average_price_flats as i64),
+        ]);
+    }
+
+    df
+}
+
 fn create_reference_mapping(filename: &str) -> HashMap<(i32, i32), UKHPIRecord> {
     let mut map = HashMap::new();
     let mut reader = Reader::from_path(filename).unwrap();
@@ -93,6 +329,10 @@ fn create_ppd_mapping(filename: &str) -> HashMap<(String, String), Vec<PPDSRecor
 struct OutputCSV {
     labels: Vec<String>,
     rows: Vec<Vec<String>>,
+    // parallel to `labels` (minus the leading "date" column): true for columns that
+    // should be drawn as a continuous line when charted (reference averages), false
+    // for sparse individual sales (drawn as markers).
+    continuous: Vec<bool>,
 }
 
 impl OutputCSV {
@@ -100,19 +340,101 @@ impl OutputCSV {
         OutputCSV {
             labels: vec![String::from("date")],
             rows: Vec::new(),
+            continuous: Vec::new(),
         }
     }
 
     fn set_labels(&mut self, v: Vec<String>) {
+        self.continuous = vec![false; v.len().saturating_sub(1)];
         self.labels = v;
     }
 
+    fn set_continuous(&mut self, v: Vec<bool>) {
+        self.continuous = v;
+    }
+
+    /// Rebases every column to an index of 100 at its earliest non-empty
+    /// value, mirroring how `hpi_all`/`hpi_flats` are themselves constructed.
+    /// Columns are rebased independently, so mismatched start dates across
+    /// columns are fine.
+    fn rebase_to_100(&mut self) {
+        let num_cols = self.labels.len() - 1;
+        let mut base: Vec<Option<(NaiveDate, f32)>> = vec![None; num_cols];
+
+        for row in &self.rows {
+            let Ok(date) = NaiveDate::parse_from_str(&row[0], "%Y-%m-%d") else {
+                continue;
+            };
+
+            for (i, base_i) in base.iter_mut().enumerate() {
+                let Some(v) = row[i + 1].parse::<f32>().ok() else {
+                    continue;
+                };
+
+                match base_i {
+                    None => *base_i = Some((date, v)),
+                    Some((d, _)) if date < *d => *base_i = Some((date, v)),
+                    _ => {}
+                }
+            }
+        }
+
+        for row in &mut self.rows {
+            for i in 0..num_cols {
+                let Some(v) = row[i + 1].parse::<f32>().ok() else {
+                    continue;
+                };
+                if let Some((_, v0)) = base[i] {
+                    if v0 != 0.0 {
+                        row[i + 1] = format!("{}", 100f32 * v / v0);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Replaces every numeric value in every column with its rolling
+    /// `stat` over `config`'s window, in place. Points whose date has no
+    /// underlying value in this column are left untouched (there is
+    /// nothing to smooth into them), so gaps are skipped rather than
+    /// carrying stale values forward.
+    fn smooth_columns(&mut self, config: SmoothingConfig) {
+        let num_cols = self.labels.len() - 1;
+
+        for i in 0..num_cols {
+            let mut indexed: Vec<(NaiveDate, f32, usize)> = Vec::new();
+
+            for (row_idx, row) in self.rows.iter().enumerate() {
+                let Ok(date) = NaiveDate::parse_from_str(&row[0], "%Y-%m-%d") else {
+                    continue;
+                };
+                let Ok(v) = row[i + 1].parse::<f32>() else {
+                    continue;
+                };
+                indexed.push((date, v, row_idx));
+            }
+
+            if indexed.is_empty() {
+                continue;
+            }
+
+            indexed.sort_by(|a, b| a.0.cmp(&b.0));
+            let points: Vec<(NaiveDate, f32)> = indexed.iter().map(|(d, v, _)| (*d, *v)).collect();
+            let smoothed = rolling_window(&points, config);
+
+            for ((_, _, row_idx), (_, smoothed_v)) in indexed.iter().zip(smoothed.iter()) {
+                self.rows[*row_idx][i + 1] = format!("{smoothed_v}");
+            }
+        }
+    }
+
     fn add_row(&mut self, string: Vec<String>) {
         self.rows.push(string);
     }
 
     fn add_entries(&mut self, flat_name: String, building: String, ppds: Vec<PPDSRecord>) {
         self.labels.push(format!("{flat_name}, {building}"));
+        self.continuous.push(false);
         for row in &mut self.rows {
             row.push("".to_string());
         }
@@ -132,6 +454,7 @@ impl OutputCSV {
         percentages: Vec<(NaiveDate, f32)>,
     ) {
         self.labels.push(format!("{flat_name}, {building}"));
+        self.continuous.push(false);
         for row in &mut self.rows {
             row.push("".to_string());
         }
@@ -154,6 +477,7 @@ impl OutputCSV {
         F: Fn(UKHPIRecord) -> i32,
     {
         self.labels.push(format!("{flat_name}, {building}"));
+        self.continuous.push(true);
         for row in &mut self.rows {
             row.push("".to_string());
         }
@@ -167,6 +491,329 @@ impl OutputCSV {
     }
 }
 
+fn csv_path_to_svg(csv_path: &str) -> String {
+    match csv_path.strip_suffix(".csv") {
+        Some(stem) => format!("{stem}.svg"),
+        None => format!("{csv_path}.svg"),
+    }
+}
+
+/// Picks the actual tick marks for the X axis: yearly (Jan 1) boundaries once
+/// the first and last dates' calendar years are two or more apart, otherwise every month
+/// boundary so a short span still gets more than one or two ticks.
+fn date_key_points(min_date: NaiveDate, max_date: NaiveDate) -> Vec<NaiveDate> {
+    let span_years = max_date.year() - min_date.year();
+
+    if span_years >= 2 {
+        return (min_date.year()..=max_date.year())
+            .filter_map(|y| NaiveDate::from_ymd_opt(y, 1, 1))
+            .filter(|d| *d >= min_date && *d <= max_date)
+            .collect();
+    }
+
+    let mut points = Vec::new();
+    let mut cursor = NaiveDate::from_ymd_opt(min_date.year(), min_date.month(), 1).unwrap();
+    while cursor <= max_date {
+        points.push(cursor);
+        cursor = if cursor.month() == 12 {
+            NaiveDate::from_ymd_opt(cursor.year() + 1, 1, 1).unwrap()
+        } else {
+            NaiveDate::from_ymd_opt(cursor.year(), cursor.month() + 1, 1).unwrap()
+        };
+    }
+    points
+}
+
+/// Renders an `OutputCSV` to a line chart: sparse (non-continuous) columns are
+/// drawn as point markers (individual flat sales), continuous columns (reference
+/// averages) are drawn as lines, all sharing a calendar X axis floored to month
+/// start.
+fn render_chart(output: &OutputCSV, svg_path: &str) {
+    let mut series: Vec<Vec<(NaiveDate, f32)>> = vec![Vec::new(); output.labels.len() - 1];
+    let mut min_date: Option<NaiveDate> = None;
+    let mut max_date: Option<NaiveDate> = None;
+    let mut min_val = f32::MAX;
+    let mut max_val = f32::MIN;
+
+    for row in &output.rows {
+        let Ok(date) = NaiveDate::parse_from_str(&row[0], "%Y-%m-%d") else {
+            continue;
+        };
+        let date = date.with_day(1).unwrap_or(date);
+
+        min_date = Some(min_date.map_or(date, |m: NaiveDate| m.min(date)));
+        max_date = Some(max_date.map_or(date, |m: NaiveDate| m.max(date)));
+
+        for (i, cell) in row.iter().enumerate().skip(1) {
+            if cell.is_empty() {
+                continue;
+            }
+            if let Ok(v) = cell.parse::<f32>() {
+                series[i - 1].push((date, v));
+                min_val = min_val.min(v);
+                max_val = max_val.max(v);
+            }
+        }
+    }
+
+    let (Some(min_date), Some(max_date)) = (min_date, max_date) else {
+        return;
+    };
+    if min_val > max_val {
+        return;
+    }
+
+    let root = SVGBackend::new(svg_path, (1280, 720)).into_drawing_area();
+    _ = root.fill(&WHITE);
+
+    let key_points = date_key_points(min_date, max_date);
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption(svg_path, ("sans-serif", 20))
+        .margin(20)
+        .x_label_area_size(40)
+        .y_label_area_size(60)
+        .build_cartesian_2d(
+            (min_date..max_date).with_key_points(key_points),
+            min_val..max_val,
+        )
+        .unwrap();
+
+    chart
+        .configure_mesh()
+        .x_desc("date")
+        .y_desc("price")
+        .x_label_formatter(&|d| d.format("%b %Y").to_string())
+        .draw()
+        .unwrap();
+
+    let palette = [&RED, &BLUE, &GREEN, &MAGENTA, &CYAN, &BLACK];
+
+    for (i, points) in series.iter().enumerate() {
+        if points.is_empty() {
+            continue;
+        }
+
+        let mut sorted = points.clone();
+        sorted.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let colour = palette[i % palette.len()];
+        let label = output.labels[i + 1].clone();
+        let is_continuous = output.continuous.get(i).copied().unwrap_or(false);
+
+        if is_continuous {
+            chart
+                .draw_series(LineSeries::new(sorted, colour))
+                .unwrap()
+                .label(label)
+                .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], colour));
+        } else {
+            chart
+                .draw_series(
+                    sorted
+                        .iter()
+                        .map(|(d, v)| Circle::new((*d, *v), 4, colour.filled())),
+                )
+                .unwrap()
+                .label(label)
+                .legend(move |(x, y)| Circle::new((x + 10, y), 4, colour.filled()));
+        }
+    }
+
+    chart
+        .configure_series_labels()
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw()
+        .unwrap();
+
+    _ = root.present();
+}
+
+#[derive(Debug, Clone)]
+struct TrendFit {
+    annualized_rate: f64,
+    r_squared: f64,
+    fallback: bool,
+}
+
+/// Fits `y = intercept + slope * x` via ordinary least squares over `points`
+/// (`x` in years since the first point, `y = ln(value)`), and returns the
+/// annualized compound rate `exp(slope) - 1` plus R². Flats with exactly two
+/// sales fall back to the compound annual rate between the two endpoints (R²
+/// reported as 1.0 since two points always fit a line exactly). Returns `None`
+/// for fewer than two points, a zero-length window, or two points whose first
+/// value is not positive.
+fn fit_trend(points: &[(NaiveDate, f64)]) -> Option<TrendFit> {
+    if points.len() < 2 {
+        return None;
+    }
+
+    if points.len() == 2 {
+        let (d0, v0) = points[0];
+        let (d1, v1) = points[1];
+        let years = (d1 - d0).num_days() as f64 / 365.25;
+        if years <= 0.0 || v0 <= 0.0 {
+            return None;
+        }
+
+        return Some(TrendFit {
+            annualized_rate: (v1 / v0).powf(1.0 / years) - 1.0,
+            r_squared: 1.0,
+            fallback: true,
+        });
+    }
+
+    let base_date = points[0].0;
+    let xs: Vec<f64> = points
+        .iter()
+        .map(|(d, _)| (*d - base_date).num_days() as f64 / 365.25)
+        .collect();
+    let ys: Vec<f64> = points.iter().map(|(_, v)| v.ln()).collect();
+
+    let x_bar = xs.iter().sum::<f64>() / xs.len() as f64;
+    let y_bar = ys.iter().sum::<f64>() / ys.len() as f64;
+
+    let mut covariance = 0.0;
+    let mut variance_x = 0.0;
+    for (x, y) in xs.iter().zip(&ys) {
+        covariance += (x - x_bar) * (y - y_bar);
+        variance_x += (x - x_bar).powi(2);
+    }
+
+    if variance_x == 0.0 {
+        return None;
+    }
+
+    let slope = covariance / variance_x;
+    let intercept = y_bar - slope * x_bar;
+
+    let ss_tot: f64 = ys.iter().map(|y| (y - y_bar).powi(2)).sum();
+    let ss_res: f64 = xs
+        .iter()
+        .zip(&ys)
+        .map(|(x, y)| (y - (intercept + slope * x)).powi(2))
+        .sum();
+
+    Some(TrendFit {
+        annualized_rate: slope.exp() - 1.0,
+        r_squared: if ss_tot == 0.0 {
+            1.0
+        } else {
+            1.0 - ss_res / ss_tot
+        },
+        fallback: false,
+    })
+}
+
+#[derive(Debug, Clone, Copy)]
+enum RollingStat {
+    Mean,
+    Median,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SmoothingConfig {
+    window_months: i32,
+    centered: bool,
+    stat: RollingStat,
+}
+
+/// For each point, averages (mean or median, per `config.stat`) all points
+/// within `config.window_months` of it: trailing `[date - window, date]`, or
+/// centered `[date - window/2, date + window/2]` if `config.centered`. Months
+/// with no underlying points simply have nothing in range, so gaps are
+/// skipped rather than interpolated over.
+fn rolling_window(points: &[(NaiveDate, f32)], config: SmoothingConfig) -> Vec<(NaiveDate, f32)> {
+    let window_days = (config.window_months as f64 * 30.44) as i64;
+
+    let mut sorted = points.to_vec();
+    sorted.sort_by(|a, b| a.0.cmp(&b.0));
+
+    sorted
+        .iter()
+        .map(|(date, _)| {
+            let (lo, hi) = if config.centered {
+                let half = chrono::Duration::days(window_days / 2);
+                (*date - half, *date + half)
+            } else {
+                (*date - chrono::Duration::days(window_days), *date)
+            };
+
+            let mut in_window: Vec<f32> = sorted
+                .iter()
+                .filter(|(d, _)| *d >= lo && *d <= hi)
+                .map(|(_, v)| *v)
+                .collect();
+
+            let value = match config.stat {
+                RollingStat::Mean => in_window.iter().sum::<f32>() / in_window.len() as f32,
+                RollingStat::Median => {
+                    in_window.sort_by(|a, b| a.total_cmp(b));
+                    let mid = in_window.len() / 2;
+                    if in_window.len() % 2 == 0 {
+                        (in_window[mid - 1] + in_window[mid]) / 2f32
+                    } else {
+                        in_window[mid]
+                    }
+                }
+            };
+
+            (*date, value)
+        })
+        .collect()
+}
+
+/// Buckets `values` into `bins` equal-width bins spanning their min/max and
+/// returns `(bin_midpoint, count)` for each bin in order. The maximum value
+/// is clamped into the last bin so it isn't dropped by a boundary rounding
+/// error. Returns an empty `Vec` for an empty slice or zero bins, and a single
+/// `(value, len)` bin when all values are equal.
+fn histogram(values: &[f32], bins: usize) -> Vec<(f32, i32)> {
+    if values.is_empty() || bins == 0 {
+        return Vec::new();
+    }
+
+    let min = values.iter().cloned().fold(f32::MAX, f32::min);
+    let max = values.iter().cloned().fold(f32::MIN, f32::max);
+    let width = (max - min) / bins as f32;
+
+    if width == 0.0 {
+        return vec![(min, values.len() as i32)];
+    }
+
+    let mut counts = vec![0i32; bins];
+    for v in values {
+        let idx = (((v - min) / width) as usize).min(bins - 1);
+        counts[idx] += 1;
+    }
+
+    counts
+        .into_iter()
+        .enumerate()
+        .map(|(i, count)| (min + width * (i as f32 + 0.5), count))
+        .collect()
+}
+
+fn write_histogram(values: &[f32], bins: usize, output_filepath: &str) {
+    let mut writer = csv::Writer::from_path(output_filepath).unwrap();
+    _ = writer.write_record(["bin_midpoint", "count"]);
+    for (midpoint, count) in histogram(values, bins) {
+        _ = writer.write_record([format!("{midpoint}"), format!("{count}")]);
+    }
+
+    _ = writer.flush();
+}
+
+/// Optional post-processing steps for `filter_and_write`'s output, bundled
+/// up so adding another one doesn't mean adding another positional argument.
+#[derive(Debug, Clone, Default)]
+struct OutputOptions {
+    rebase_to_100: bool,
+    smoothing: Option<SmoothingConfig>,
+    histogram_bins: Option<usize>,
+}
+
 /// Both `length_filter` and `date_distance_filter` **REJECT** datapoints that return `true`.
 /// `number_to_return`: Some(`i32`). If some, returns the top n weighted by `length of time between first and last` * `number of sales` * `0.5`
 fn filter_and_write<F, D>(
@@ -175,6 +822,7 @@ fn filter_and_write<F, D>(
     length_filter: F,
     date_distance_filter: D,
     number_to_return: Option<i32>,
+    options: OutputOptions,
     output_filepath: &str,
 ) where
     F: Fn(usize) -> bool,
@@ -231,6 +879,7 @@ fn filter_and_write<F, D>(
     };
 
     let mut percentage_change_output = OutputCSV::new();
+    let mut trend_rows: Vec<Vec<String>> = Vec::new();
 
     for dp in datapoints_to_process.clone() {
         min_date = match min_date.clone() {
@@ -266,29 +915,78 @@ fn filter_and_write<F, D>(
         }
 
         for d in datapoints_to_process.clone() {
-            let mut previous_record = None;
+            let flat_points: Vec<(NaiveDate, f64)> = d
+                .records
+                .iter()
+                .map(|r| (r.date, r.price_paid as f64))
+                .collect();
+
+            let mut ref_points: Vec<(NaiveDate, f64)> = ref_map
+                .values()
+                .filter(|r| d.first <= r.time && r.time <= d.last)
+                .map(|r| (r.time, r.average_price_flats as f64))
+                .collect();
+            ref_points.sort_by(|a, b| a.0.cmp(&b.0));
+
+            let flat_fit = fit_trend(&flat_points);
+            let ref_fit = fit_trend(&ref_points);
+
+            if let (Some(flat_fit), Some(ref_fit)) = (flat_fit, ref_fit) {
+                trend_rows.push(vec![
+                    d.flat.clone(),
+                    d.building.clone(),
+                    ref_map.values().next().unwrap().region.clone(),
+                    format!("{}", flat_fit.annualized_rate),
+                    format!("{}", flat_fit.r_squared),
+                    format!("{}", ref_fit.annualized_rate),
+                    format!("{}", flat_fit.annualized_rate - ref_fit.annualized_rate),
+                    format!("{}", flat_fit.fallback),
+                ]);
+            }
+
+            // Join each sale to its region's average_price_flats for that month
+            // instead of indexing `ref_map` by hand, so a sale whose month has
+            // no matching reference row comes out of the join with no reference
+            // value rather than panicking on a missing key. No percentage point
+            // is emitted for such a sale, nor for the sale after it.
+            let mut flat_sales = DataFrame::new(vec!["date", "month", "price_paid"]);
+            for r in &d.records {
+                flat_sales.push_row(vec![
+                    Cell::Date(r.date),
+                    Cell::Date(r.date.with_day(1).unwrap_or(r.date)),
+                    Cell::Int(r.price_paid as i64),
+                ]);
+            }
+
+            let ref_series =
+                region_flats_series(&ref_map, "average_price_flats").floor_to_month("date");
+
+            let mut joined_sales: Vec<(NaiveDate, f64, Option<f64>)> = flat_sales
+                .join(&ref_series, "month", "date")
+                .rows
+                .iter()
+                .filter_map(|row| Some((row[0].as_date()?, row[2].as_f64()?, row[3].as_f64())))
+                .collect();
+            joined_sales.sort_by(|a, b| a.0.cmp(&b.0));
+
+            let mut previous_sale: Option<(NaiveDate, f64, Option<f64>)> = None;
             let mut percentages = Vec::new();
 
-            for r in d.records {
-                previous_record = match previous_record {
+            for (date, price, ref_avg) in joined_sales {
+                previous_sale = match previous_sale {
                     None => {
-                        percentages.push((r.date, 0f32));
-                        Some(r)
+                        percentages.push((date, 0f32));
+                        Some((date, price, ref_avg))
                     }
-                    Some(prev_r) => {
-                        let change: f32 =
-                            (r.price_paid - prev_r.price_paid) as f32 / prev_r.price_paid as f32;
-                        let new_ref = ref_map[&(r.date.month() as i32, r.date.year() as i32)]
-                            .average_price_flats;
-                        let original_ref = ref_map
-                            [&(prev_r.date.month() as i32, prev_r.date.year() as i32)]
-                            .average_price_flats;
-                        let change_of_ref: f32 =
-                            (new_ref - original_ref) as f32 / original_ref as f32;
-
-                        let percentage_change_diff = change - change_of_ref;
-                        percentages.push((r.date, percentage_change_diff * 100f32));
-                        Some(prev_r)
+                    Some((_, prev_price, prev_ref_avg)) => {
+                        let change = (price - prev_price) / prev_price;
+
+                        if let (Some(new_ref), Some(original_ref)) = (ref_avg, prev_ref_avg) {
+                            let change_of_ref = (new_ref - original_ref) / original_ref;
+                            percentages.push((date, ((change - change_of_ref) * 100.0) as f32));
+                        }
+
+                        Some((date, price, ref_avg))
                     }
                 }
             }
@@ -319,6 +1017,12 @@ fn filter_and_write<F, D>(
         );
     }
 
+    if options.rebase_to_100 {
+        output.rebase_to_100();
+    }
+
+    render_chart(&output, &csv_path_to_svg(output_filepath));
+
     let mut writer = csv::Writer::from_path(output_filepath).unwrap();
     _ = writer.write_record(output.labels);
     for row in output.rows {
@@ -327,7 +1031,14 @@ fn filter_and_write<F, D>(
 
     _ = writer.flush();
 
-    let mut writer = csv::Writer::from_path("pc/".to_string() + output_filepath).unwrap();
+    if let Some(config) = options.smoothing {
+        percentage_change_output.smooth_columns(config);
+    }
+
+    let pc_filepath = "pc/".to_string() + output_filepath;
+    render_chart(&percentage_change_output, &csv_path_to_svg(&pc_filepath));
+
+    let mut writer = csv::Writer::from_path(pc_filepath).unwrap();
     _ = writer.write_record(percentage_change_output.labels);
     for row in percentage_change_output.rows {
         let e = writer.write_record(&row);
@@ -335,6 +1046,61 @@ fn filter_and_write<F, D>(
     }
 
     _ = writer.flush();
+
+    let mut writer = csv::Writer::from_path("trend/".to_string() + output_filepath).unwrap();
+    _ = writer.write_record([
+        "flat",
+        "building",
+        "region",
+        "flat_annualized_rate",
+        "flat_r_squared",
+        "region_annualized_rate",
+        "outperformance",
+        "fallback_to_endpoints",
+    ]);
+    for row in &trend_rows {
+        _ = writer.write_record(row);
+    }
+
+    _ = writer.flush();
+
+    if let Some(bins) = options.histogram_bins {
+        let mut outperformance_by_region: HashMap<String, Vec<f32>> = HashMap::new();
+        for row in &trend_rows {
+            if let Ok(outperformance) = row[6].parse::<f32>() {
+                outperformance_by_region
+                    .entry(row[2].clone())
+                    .or_default()
+                    .push(outperformance);
+            }
+        }
+
+        let filename = output_filepath.rsplit('/').next().unwrap_or(output_filepath);
+        for (region, values) in outperformance_by_region {
+            write_histogram(&values, bins, &format!("hist/{region}-{filename}"));
+        }
+    }
+}
+
+/// Picks out an estate's individual sales (one row per real transaction, at
+/// its exact sale date), renamed to `series_name` so it can be joined
+/// alongside the other estates/regions. Deliberately left at flat/day
+/// granularity rather than grouped by month: grouping here would average
+/// together sales of different flats, which isn't the same thing as a
+/// single flat's price moving over time.
+fn estate_sale_series(filename: &str, series_name: &str) -> DataFrame {
+    let mut sales = create_ppd_dataframe(filename).select(&["date", "price_paid"]);
+    sales.rename("price_paid", series_name);
+    sales
+}
+
+/// Picks out a region's flats average, renamed to `series_name`, from an
+/// already-built reference mapping.
+fn region_flats_series(map: &HashMap<(i32, i32), UKHPIRecord>, series_name: &str) -> DataFrame {
+    let mut series =
+        reference_map_to_dataframe(map).select(&["date", "average_price_flats"]);
+    series.rename("average_price_flats", series_name);
+    series
 }
 
 fn write_all_sale_map(
@@ -342,101 +1108,25 @@ fn write_all_sale_map(
     lon: HashMap<(i32, i32), UKHPIRecord>,
     eng: HashMap<(i32, i32), UKHPIRecord>,
 ) {
-    // algorithm:
-    // * get all barbican
-    // * add date, barbican, gle, col, lon, eng
-    // * add date, price for all
-
-    let mut out = OutputCSV::new();
-    let barbican_ppd = create_ppd_mapping("estates/barbican_adapted.csv");
-    let gle_ppd = create_ppd_mapping("estates/golden_lane.csv");
-
-    out.set_labels(vec![
-        "date".to_string(),
-        "barbican".to_string(),
-        "golden_lane".to_string(),
-        "city_of_london_flats".to_string(),
-        "london_flats".to_string(),
-        "england_flats".to_string(),
-    ]);
+    let barbican = estate_sale_series("estates/barbican_adapted.csv", "barbican");
+    let golden_lane = estate_sale_series("estates/golden_lane.csv", "golden_lane");
+    let city_of_london = region_flats_series(&col, "city_of_london_flats");
+    let london = region_flats_series(&lon, "london_flats");
+    let england = region_flats_series(&eng, "england_flats");
 
-    for (_, v) in barbican_ppd {
-        for u in v {
-            let pp = format!("{}", u.price_paid);
-            let date = u.date.format("%Y-%m-%d").to_string();
-            out.add_row(vec![
-                date,
-                pp,
-                "".to_string(),
-                "".to_string(),
-                "".to_string(),
-                "".to_string(),
-            ]);
-        }
-    }
-
-    for (_, v) in gle_ppd {
-        for u in v {
-            let pp = format!("{}", u.price_paid);
-            let date = u.date.format("%Y-%m-%d").to_string();
-            out.add_row(vec![
-                date,
-                "".to_string(),
-                pp,
-                "".to_string(),
-                "".to_string(),
-                "".to_string(),
-            ]);
-        }
-    }
-
-    let mut col_v: Vec<&UKHPIRecord> = col.values().collect();
-    col_v.sort_by(|a, b| a.time.cmp(&b.time));
-    for v in col_v {
-        let pp = format!("{}", v.average_price_flats);
-        let date = v.time.format("%Y-%m-%d").to_string();
-        out.add_row(vec![
-            date,
-            "".to_string(),
-            "".to_string(),
-            pp,
-            "".to_string(),
-            "".to_string(),
-        ]);
-    }
+    let joined = barbican
+        .join(&golden_lane, "date", "date")
+        .join(&city_of_london, "date", "date")
+        .join(&london, "date", "date")
+        .join(&england, "date", "date");
 
-    let mut lon_v: Vec<&UKHPIRecord> = lon.values().collect();
-    lon_v.sort_by(|a, b| a.time.cmp(&b.time));
-    for v in lon_v {
-        let pp = format!("{}", v.average_price_flats);
-        let date = v.time.format("%Y-%m-%d").to_string();
-        out.add_row(vec![
-            date,
-            "".to_string(),
-            "".to_string(),
-            "".to_string(),
-            pp,
-            "".to_string(),
-        ]);
-    }
-
-    let mut eng_v: Vec<&UKHPIRecord> = eng.values().collect();
-    eng_v.sort_by(|a, b| a.time.cmp(&b.time));
-    for v in eng_v {
-        let pp = format!("{}", v.average_price_flats);
-        let date = v.time.format("%Y-%m-%d").to_string();
-        out.add_row(vec![
-            date,
-            "".to_string(),
-            "".to_string(),
-            "".to_string(),
-            "".to_string(),
-            pp,
-        ]);
-    }
+    let mut out = joined.to_output_csv("date");
+    out.set_continuous(vec![false, false, true, true, true]);
 
     println!("{}", out.rows.len());
 
+    render_chart(&out, "output/all-prices.svg");
+
     let mut writer = csv::Writer::from_path("output/all-prices.csv").unwrap();
     _ = writer.write_record(&out.labels);
     for row in out.rows {
@@ -459,6 +1149,15 @@ fn main() {
         |x| x < 3,
         |x| x < 7300,
         Some(10),
+        OutputOptions {
+            rebase_to_100: false,
+            smoothing: Some(SmoothingConfig {
+                window_months: 6,
+                centered: false,
+                stat: RollingStat::Mean,
+            }),
+            histogram_bins: Some(10),
+        },
         "output/barbican-output-4.csv",
     );
 
@@ -468,8 +1167,116 @@ fn main() {
         |x| x < 3,
         |x| x < 7300,
         None,
+        OutputOptions {
+            rebase_to_100: false,
+            smoothing: Some(SmoothingConfig {
+                window_months: 6,
+                centered: false,
+                stat: RollingStat::Mean,
+            }),
+            histogram_bins: Some(10),
+        },
         "output/golden-lane-output-4.csv",
     );
 
     write_all_sale_map(col_map, london_map, eng_map);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn fit_trend_needs_at_least_two_points() {
+        assert!(fit_trend(&[]).is_none());
+        assert!(fit_trend(&[(date(2020, 1, 1), 100.0)]).is_none());
+    }
+
+    #[test]
+    fn fit_trend_two_points_falls_back_to_endpoint_rate() {
+        let fit = fit_trend(&[(date(2020, 1, 1), 100.0), (date(2021, 1, 1), 110.0)]).unwrap();
+        assert!(fit.fallback);
+        assert_eq!(fit.r_squared, 1.0);
+        assert!((fit.annualized_rate - 0.10).abs() < 0.01);
+    }
+
+    #[test]
+    fn fit_trend_two_points_rejects_non_positive_span_or_value() {
+        assert!(fit_trend(&[(date(2020, 1, 1), 100.0), (date(2020, 1, 1), 110.0)]).is_none());
+        assert!(fit_trend(&[(date(2020, 1, 1), 0.0), (date(2021, 1, 1), 110.0)]).is_none());
+    }
+
+    #[test]
+    fn fit_trend_perfect_exponential_growth_has_r_squared_one() {
+        let points = [
+            (date(2018, 1, 1), 100.0),
+            (date(2019, 1, 1), 110.0),
+            (date(2020, 1, 1), 121.0),
+            (date(2021, 1, 1), 133.1),
+        ];
+        let fit = fit_trend(&points).unwrap();
+        assert!(!fit.fallback);
+        assert!((fit.r_squared - 1.0).abs() < 1e-6);
+        assert!((fit.annualized_rate - 0.10).abs() < 0.01);
+    }
+
+    #[test]
+    fn rolling_window_trailing_mean_only_looks_backwards() {
+        let points = [
+            (date(2020, 1, 1), 10.0),
+            (date(2020, 2, 1), 20.0),
+            (date(2020, 3, 1), 30.0),
+        ];
+        let config = SmoothingConfig {
+            window_months: 1,
+            centered: false,
+            stat: RollingStat::Mean,
+        };
+        let smoothed = rolling_window(&points, config);
+        assert_eq!(smoothed[0], (date(2020, 1, 1), 10.0));
+        assert_eq!(smoothed[2].0, date(2020, 3, 1));
+        assert!((smoothed[2].1 - 25.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn rolling_window_centered_median_looks_both_ways() {
+        let points = [
+            (date(2020, 1, 1), 10.0),
+            (date(2020, 2, 1), 100.0),
+            (date(2020, 3, 1), 20.0),
+        ];
+        let config = SmoothingConfig {
+            window_months: 3,
+            centered: true,
+            stat: RollingStat::Median,
+        };
+        let smoothed = rolling_window(&points, config);
+        assert_eq!(smoothed[1].0, date(2020, 2, 1));
+        assert_eq!(smoothed[1].1, 20.0);
+    }
+
+    #[test]
+    fn histogram_buckets_values_into_equal_width_bins() {
+        let values = [0.0, 1.0, 2.0, 3.0, 9.0, 10.0];
+        let bins = histogram(&values, 2);
+        assert_eq!(bins.len(), 2);
+        assert_eq!(bins[0].1, 4);
+        assert_eq!(bins[1].1, 2);
+    }
+
+    #[test]
+    fn histogram_handles_empty_input_and_zero_bins() {
+        assert!(histogram(&[], 5).is_empty());
+        assert!(histogram(&[1.0, 2.0], 0).is_empty());
+    }
+
+    #[test]
+    fn histogram_constant_values_collapse_into_one_bin() {
+        let bins = histogram(&[5.0, 5.0, 5.0], 4);
+        assert_eq!(bins, vec![(5.0, 3)]);
+    }
+}